@@ -1,12 +1,12 @@
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
 use ic_cdk::api::caller;
-use ic_cdk_macros::{init, query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
 use rand::Rng;
 use std::collections::HashMap;
 use uuid::Uuid;
-use std::{borrow::Cow, cell::RefCell}; 
+use std::{borrow::Cow, cell::RefCell};
 
 #[derive(CandidType, Deserialize, Debug, Clone)]
 struct Player {
@@ -29,6 +29,74 @@ enum Turn {
     Player2,
 }
 
+// The two legal Chopsticks actions: tap an opponent's hand with one of your
+// own, or redistribute your own fingers between your two hands.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+enum Move {
+    Tap { from_hand: u8, target_hand: u8 },
+    Split { left: u8, right: u8 },
+}
+
+// Machine-readable failure modes surfaced to callers instead of opaque Ok(())s.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+enum GameError {
+    GameNotFound,
+    NotYourTurn,
+    GameNotInProgress,
+    InactiveHand,
+    InvalidMove,
+    AlreadyFull,
+    ServiceUninitialized,
+    NotRoomMaster,
+    WrongPassword,
+}
+
+// Failure modes specific to joining a room from the lobby.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+enum JoinRoomError {
+    DoesntExist,
+    RoomFull,
+    WrongPassword,
+}
+
+// A chess-clock style budget for one player: a bank of time that drains while
+// it is their turn, topped up by `per_move_increment_nanos` after each move.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+struct ClockSpec {
+    initial_nanos: i64,
+    per_move_increment_nanos: i64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+struct PlayerClock {
+    remaining_nanos: i64,
+    per_move_increment_nanos: i64,
+    // Kept alongside `remaining_nanos` so a rematch can reset the bank
+    // instead of carrying over whatever was left (or owed) at game end.
+    initial_nanos: i64,
+}
+
+impl PlayerClock {
+    fn new(spec: ClockSpec) -> Self {
+        PlayerClock {
+            remaining_nanos: spec.initial_nanos,
+            per_move_increment_nanos: spec.per_move_increment_nanos,
+            initial_nanos: spec.initial_nanos,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.remaining_nanos = self.initial_nanos;
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct GameClock {
+    player1: PlayerClock,
+    player2: PlayerClock,
+    turn_started_at: Option<u64>,
+}
+
 #[derive(CandidType, Deserialize, Debug, Clone)]
 struct Game {
     session_id: String,
@@ -36,9 +104,35 @@ struct Game {
     player2: Option<Player>,
     state: GameState,
     current_turn: Turn,
+    clock: Option<GameClock>,
+    // Bumped on every successful join/move so a caller can cheaply long-poll
+    // by comparing against the revision it already has.
+    revision: u64,
+    // Updated alongside `revision`; lets the reaper tell abandoned games
+    // apart from ones that are merely waiting on a slow human.
+    last_activity: u64,
+    // Lobby metadata. `player1` is always the room's creator/master.
+    room_name: String,
+    is_private: bool,
+    room_password: Option<String>,
+    // Principals who have voted to start a rematch after `Finished`; reset
+    // once both players have voted and the rematch begins.
+    rematch_votes: Vec<Principal>,
+    // Whether a move has been played since the current round started; used
+    // to tell "opponent just joined" apart from "the round is underway" for
+    // `kick_opponent`, since `revision` keeps climbing across joins/kicks.
+    has_moved: bool,
     // Additional fields to represent the state of the game
 }
 
+// How long a `Finished` game is kept around before the reaper removes it.
+const FINISHED_GAME_GRACE_NANOS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+// How long a `WaitingForPlayer` game is kept around before it is considered
+// abandoned and removed.
+const WAITING_GAME_TIMEOUT_NANOS: u64 = 30 * 60 * 1_000_000_000; // 30 minutes
+// How often the reaper sweeps `games` for entries past the grace periods above.
+const REAPER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
 #[derive(Default, CandidType, Deserialize)]
 struct ChopsticksGameService {
     games: HashMap<String, Game>,
@@ -53,7 +147,14 @@ impl Storable for ChopsticksGameService {
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded { max_size: 10000, is_fixed_size: true };
+    // This blob holds every game at once, and each `Game` has grown a clock,
+    // room name/password, rematch votes and more since this bound was first
+    // set — 10000 bytes is now easily exceeded by a handful of active games,
+    // which would panic on `Encode!(...).unwrap()` in `insert`. Sized
+    // generously for many in-flight games; it is not fixed-size, since a
+    // `HashMap`-backed blob's encoded length varies with how many games it
+    // holds.
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded { max_size: 1_000_000, is_fixed_size: false };
 }
 
 
@@ -89,134 +190,715 @@ fn init() {
     GAME_SERVICE.with(|service| {
         service.borrow_mut().insert("chopsticks_game_service".to_string(), init_state);
     });
+    ic_cdk_timers::set_timer_interval(REAPER_INTERVAL, reap_stale_games);
+}
+
+// IC timers don't survive a canister upgrade, so the reaper has to be
+// re-registered here too, or it silently stops sweeping after the first
+// upgrade. The stable map itself needs no action: stable structures persist
+// across upgrades on their own.
+#[post_upgrade]
+fn post_upgrade() {
+    ic_cdk_timers::set_timer_interval(REAPER_INTERVAL, reap_stale_games);
+}
+
+// Walks `games` removing `Finished` entries past their grace period and
+// `WaitingForPlayer` entries that never found a second player in time, so
+// stable memory doesn't grow without bound.
+fn reap_stale_games() {
+    let now = ic_cdk::api::time();
+    GAME_SERVICE.with(|service| {
+        let mut borrow = service.borrow_mut();
+        if let Some(mut game_service) = borrow.get(&"chopsticks_game_service".to_string()) {
+            game_service.games.retain(|_, game| {
+                let age = now.saturating_sub(game.last_activity);
+                match game.state {
+                    GameState::Finished { .. } => age < FINISHED_GAME_GRACE_NANOS,
+                    GameState::WaitingForPlayer => age < WAITING_GAME_TIMEOUT_NANOS,
+                    GameState::InProgress => true,
+                }
+            });
+            borrow.insert("chopsticks_game_service".to_string(), game_service);
+        }
+    });
 }
+
 impl Game {
-    fn new() -> Self {
+    fn new(caller_id: Principal, now: u64, clock_spec: Option<ClockSpec>) -> Self {
+        Self::new_room(caller_id, now, clock_spec, "Untitled Room".to_string(), false, None)
+    }
+
+    fn new_room(
+        caller_id: Principal,
+        now: u64,
+        clock_spec: Option<ClockSpec>,
+        room_name: String,
+        is_private: bool,
+        room_password: Option<String>,
+    ) -> Self {
         let mut rng = rand::thread_rng();
         Game {
             session_id: Uuid::new_v4().to_string(),
-            player1: Player { id: caller(), game: None, left_hand: 1, right_hand: 1 },
+            player1: Player { id: caller_id, game: None, left_hand: 1, right_hand: 1 },
             player2: None,
             state: GameState::WaitingForPlayer,
             current_turn: if rng.gen() { Turn::Player1 } else { Turn::Player2 },
+            clock: clock_spec.map(|spec| GameClock {
+                player1: PlayerClock::new(spec),
+                player2: PlayerClock::new(spec),
+                turn_started_at: None,
+            }),
+            revision: 0,
+            last_activity: now,
+            room_name,
+            is_private,
+            room_password,
+            rematch_votes: Vec::new(),
+            has_moved: false,
             // Initialize hands, other fields as necessary
         }
     }
 
-    fn join(&mut self, player: Player) {
-        if self.state == GameState::WaitingForPlayer && self.player2.is_none() {
-            self.player2 = Some(player);
-            self.state = GameState::InProgress;
+    fn mark_activity(&mut self, now: u64) {
+        self.revision = self.revision.wrapping_add(1);
+        self.last_activity = now;
+    }
+
+    // Joins without a password, for callers (like the legacy `join_game`
+    // endpoint) that have no way to carry one. Rejects outright if the room
+    // is password-protected, since there is nothing to check it against;
+    // such rooms must be joined through `join_room` instead.
+    fn join(&mut self, player: Player, now: u64) -> Result<(), GameError> {
+        self.join_checked(player, None, now)
+    }
+
+    // The single join path: every join, password-bearing or not, goes
+    // through here so a private room can't be entered without the check.
+    fn join_checked(&mut self, player: Player, password: Option<String>, now: u64) -> Result<(), GameError> {
+        if self.room_password.is_some() && self.room_password != password {
+            return Err(GameError::WrongPassword);
+        }
+        if self.state != GameState::WaitingForPlayer || self.player2.is_some() {
+            return Err(GameError::AlreadyFull);
         }
+        self.player2 = Some(player);
+        self.state = GameState::InProgress;
+        self.has_moved = false;
+        if let Some(clock) = &mut self.clock {
+            clock.turn_started_at = Some(now);
+        }
+        self.mark_activity(now);
+        Ok(())
+    }
+
+    // Deducts the wall-clock time elapsed since the current turn began from
+    // the active player's bank, flagging them if it has run out. Returns
+    // whether the active player was flagged.
+    fn tick_active_clock(&mut self, now: u64) -> bool {
+        let current_turn = self.current_turn.clone();
+        let flagged = if let Some(clock) = &mut self.clock {
+            if let Some(started) = clock.turn_started_at {
+                let elapsed = now.saturating_sub(started) as i64;
+                let active = match current_turn {
+                    Turn::Player1 => &mut clock.player1,
+                    Turn::Player2 => &mut clock.player2,
+                };
+                active.remaining_nanos -= elapsed;
+                active.remaining_nanos < 0
+            } else {
+                false
+            }
+        } else {
+            false
+        };
+
+        if flagged {
+            let winner = match current_turn {
+                Turn::Player1 => self.player2.as_ref().map(|p| p.id),
+                Turn::Player2 => Some(self.player1.id),
+            };
+            if let Some(winner) = winner {
+                self.state = GameState::Finished { winner };
+            }
+        }
+        flagged
     }
 
-    fn make_move(&mut self, player_id: Principal, hand: u8, target_hand: u8) {
+    fn make_move(&mut self, player_id: Principal, mv: Move, now: u64) -> Result<(), GameError> {
         if self.state != GameState::InProgress {
-            return;
+            return Err(GameError::GameNotInProgress);
+        }
+
+        if self.tick_active_clock(now) {
+            // The active player's clock ran out on this very call: the game
+            // just transitioned to `Finished`, which is a real outcome, not
+            // an error, and the caller needs `Ok` so it actually persists.
+            self.mark_activity(now);
+            return Ok(());
         }
 
         // Determine if it's player1's or player2's turn and if the move is valid
-        let (active_player, opponent) = match self.current_turn {
+        let current_turn = self.current_turn.clone();
+        let (active_player, opponent) = match current_turn {
             Turn::Player1 if self.player1.id == player_id => (&mut self.player1, self.player2.as_mut()),
             Turn::Player2 if self.player2.as_ref().map_or(false, |p| p.id == player_id) => (self.player2.as_mut().unwrap(), Some(&mut self.player1)),
-            _ => return, // Not the player's turn or player not found
+            _ => return Err(GameError::NotYourTurn), // Not the player's turn or player not found
         };
 
-        // Assuming hand and target_hand are 0 for left hand and 1 for right hand, adjust as needed
-        let active_hand = if hand == 0 { active_player.left_hand } else { active_player.right_hand };
-        if active_hand == 0 { return; } // Cannot make a move with an inactive hand
+        match mv {
+            Move::Tap { from_hand, target_hand } => {
+                // Assuming from_hand and target_hand are 0 for left hand and 1 for right hand, adjust as needed
+                let active_hand = if from_hand == 0 { active_player.left_hand } else { active_player.right_hand };
+                if active_hand == 0 {
+                    return Err(GameError::InactiveHand);
+                }
 
-        if let Some(opponent) = opponent {
-            let opponent_hand = if target_hand == 0 { &mut opponent.left_hand } else { &mut opponent.right_hand };
-            *opponent_hand += active_hand;
-            if *opponent_hand >= 5 { *opponent_hand = 0; } // Reset hand if it reaches the threshold
+                let opponent = match opponent {
+                    Some(opponent) => opponent,
+                    None => return Err(GameError::GameNotInProgress),
+                };
+                let opponent_hand = if target_hand == 0 { &mut opponent.left_hand } else { &mut opponent.right_hand };
+                *opponent_hand += active_hand;
+                if *opponent_hand >= 5 { *opponent_hand = 0; } // Reset hand if it reaches the threshold
 
-            // Check if the game has ended
-            if opponent.left_hand == 0 && opponent.right_hand == 0 {
-                self.state = GameState::Finished { winner: player_id };
-            } else {
-                // Switch turns
-                self.current_turn = match self.current_turn {
-                    Turn::Player1 => Turn::Player2,
-                    Turn::Player2 => Turn::Player1,
+                // Check if the game has ended
+                if opponent.left_hand == 0 && opponent.right_hand == 0 {
+                    self.state = GameState::Finished { winner: player_id };
+                    self.has_moved = true;
+                    self.mark_activity(now);
+                    return Ok(());
+                }
+            }
+            Move::Split { left, right } => {
+                if left >= 5 || right >= 5 {
+                    return Err(GameError::InvalidMove);
+                }
+                if left + right != active_player.left_hand + active_player.right_hand {
+                    return Err(GameError::InvalidMove);
+                }
+                if left == active_player.left_hand && right == active_player.right_hand {
+                    return Err(GameError::InvalidMove);
+                }
+                active_player.left_hand = left;
+                active_player.right_hand = right;
+            }
+        }
+
+        self.has_moved = true;
+
+        // Switch turns
+        self.current_turn = match current_turn {
+            Turn::Player1 => Turn::Player2,
+            Turn::Player2 => Turn::Player1,
+        };
+        if let Some(clock) = &mut self.clock {
+            let finishing = match self.current_turn {
+                Turn::Player1 => clock.player2,
+                Turn::Player2 => clock.player1,
+            };
+            let refreshed = PlayerClock {
+                remaining_nanos: finishing.remaining_nanos + finishing.per_move_increment_nanos,
+                per_move_increment_nanos: finishing.per_move_increment_nanos,
+                initial_nanos: finishing.initial_nanos,
+            };
+            match self.current_turn {
+                Turn::Player1 => clock.player2 = refreshed,
+                Turn::Player2 => clock.player1 = refreshed,
+            };
+            clock.turn_started_at = Some(now);
+        }
+        self.mark_activity(now);
+        Ok(())
+    }
+
+    // Recomputes the active player's remaining time against the live clock
+    // and, if it has lapsed, awards the win to the opponent.
+    fn claim_timeout(&mut self, now: u64) -> Result<(), GameError> {
+        if self.state != GameState::InProgress {
+            return Err(GameError::GameNotInProgress);
+        }
+        if self.clock.is_none() {
+            return Err(GameError::InvalidMove);
+        }
+        if self.tick_active_clock(now) {
+            self.mark_activity(now);
+            Ok(())
+        } else {
+            Err(GameError::InvalidMove)
+        }
+    }
+
+    // Applies the elapsed portion of the current turn to a clone's clock so
+    // `get_game_state` can return a live, ticking remaining time.
+    fn apply_live_clock(&mut self, now: u64) {
+        if self.state != GameState::InProgress {
+            return;
+        }
+        let current_turn = self.current_turn.clone();
+        if let Some(clock) = &mut self.clock {
+            if let Some(started) = clock.turn_started_at {
+                let elapsed = now.saturating_sub(started) as i64;
+                let active = match current_turn {
+                    Turn::Player1 => &mut clock.player1,
+                    Turn::Player2 => &mut clock.player2,
                 };
+                active.remaining_nanos -= elapsed;
             }
         }
     }
+
+    // The room master may remove the opponent who just joined, as long as no
+    // move has been played yet.
+    fn kick_opponent(&mut self, caller_id: Principal, now: u64) -> Result<(), GameError> {
+        if caller_id != self.player1.id {
+            return Err(GameError::NotRoomMaster);
+        }
+        if self.state != GameState::InProgress || self.has_moved {
+            return Err(GameError::GameNotInProgress);
+        }
+        self.player2 = None;
+        self.state = GameState::WaitingForPlayer;
+        if let Some(clock) = &mut self.clock {
+            clock.turn_started_at = None;
+        }
+        self.mark_activity(now);
+        Ok(())
+    }
+
+    // Both players must vote yes before a finished game resets into a fresh
+    // round in the same room.
+    fn vote_rematch(&mut self, caller_id: Principal, now: u64) -> Result<(), GameError> {
+        if !matches!(self.state, GameState::Finished { .. }) {
+            return Err(GameError::GameNotInProgress);
+        }
+        let player2_id = self.player2.as_ref().map(|p| p.id).ok_or(GameError::GameNotInProgress)?;
+        if caller_id != self.player1.id && caller_id != player2_id {
+            return Err(GameError::NotYourTurn);
+        }
+        if !self.rematch_votes.contains(&caller_id) {
+            self.rematch_votes.push(caller_id);
+        }
+        if self.rematch_votes.len() == 2 {
+            let mut rng = rand::thread_rng();
+            self.player1.left_hand = 1;
+            self.player1.right_hand = 1;
+            if let Some(player2) = &mut self.player2 {
+                player2.left_hand = 1;
+                player2.right_hand = 1;
+            }
+            self.current_turn = if rng.gen() { Turn::Player1 } else { Turn::Player2 };
+            self.state = GameState::InProgress;
+            self.rematch_votes.clear();
+            self.has_moved = false;
+            if let Some(clock) = &mut self.clock {
+                clock.player1.reset();
+                clock.player2.reset();
+                clock.turn_started_at = Some(now);
+            }
+        }
+        self.mark_activity(now);
+        Ok(())
+    }
 }
 
 #[update]
-fn start_game() -> Result<String, String> {
-    let game = Game::new();
+fn start_game(clock_spec: Option<ClockSpec>) -> Result<String, GameError> {
+    let game = Game::new(caller(), ic_cdk::api::time(), clock_spec);
     let session_id = game.session_id.clone();
     GAME_SERVICE.with(|service| {
-        let mut games = service.borrow_mut().get(&"chopsticks_game_service".to_string());
-        if let Some(mut game_service) = games {
-            
-            game_service.games.insert(session_id.clone(), game);
-        }
-        else{
-
-        }
-    });
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        game_service.games.insert(session_id.clone(), game);
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })?;
     Ok(session_id)
 }
 
 #[update]
-fn join_game(session_id: String) -> Result<(), String> {
+fn join_game(session_id: String) -> Result<(), GameError> {
     let player = Player { id: caller(), game: Some(session_id.clone()) , left_hand:1 ,right_hand: 1};
     GAME_SERVICE.with(|service| {
-        let mut games = service.borrow_mut().get(&"chopsticks_game_service".to_string());
-        if let Some(mut game_service) = games {
-            if let Some(game) = game_service.games.get_mut(&session_id) {
-                game.join(player);
-                Ok(())
-            } else {
-                Err("Game not found".to_string())
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get_mut(&session_id)
+            .ok_or(GameError::GameNotFound)?;
+        game.join(player, ic_cdk::api::time())?;
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })
+}
+
+// Creates a joinable lobby room; the caller becomes player1 and the room's
+// master. Returns the room id other players pass to `join_room`.
+#[update]
+fn create_room(name: String, is_private: bool, password: Option<String>) -> Result<String, GameError> {
+    let game = Game::new_room(caller(), ic_cdk::api::time(), None, name, is_private, password);
+    let room_id = game.session_id.clone();
+    GAME_SERVICE.with(|service| {
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        game_service.games.insert(room_id.clone(), game);
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })?;
+    Ok(room_id)
+}
+
+#[update]
+fn join_room(room_id: String, password: Option<String>) -> Result<(), JoinRoomError> {
+    let player = Player { id: caller(), game: Some(room_id.clone()), left_hand: 1, right_hand: 1 };
+    GAME_SERVICE.with(|service| {
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(JoinRoomError::DoesntExist)?;
+        let game = game_service
+            .games
+            .get_mut(&room_id)
+            .ok_or(JoinRoomError::DoesntExist)?;
+        game.join_checked(player, password, ic_cdk::api::time()).map_err(|err| match err {
+            GameError::WrongPassword => JoinRoomError::WrongPassword,
+            _ => JoinRoomError::RoomFull,
+        })?;
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct RoomSummary {
+    room_id: String,
+    name: String,
+    creator: Principal,
+}
+
+// Lists joinable, non-private rooms for the lobby screen. Private rooms must
+// be shared out of band, the same way a session id was before this existed.
+#[query]
+fn list_open_rooms() -> Vec<RoomSummary> {
+    GAME_SERVICE.with(|service| {
+        let borrow = service.borrow();
+        let mut rooms = Vec::new();
+        if let Some(game_service) = borrow.get(&"chopsticks_game_service".to_string()) {
+            for game in game_service.games.values() {
+                if game.state == GameState::WaitingForPlayer && !game.is_private {
+                    rooms.push(RoomSummary {
+                        room_id: game.session_id.clone(),
+                        name: game.room_name.clone(),
+                        creator: game.player1.id,
+                    });
+                }
             }
         }
-        else {
-            Ok(())
-        }
+        rooms
+    })
+}
+
+#[update]
+fn kick_opponent(session_id: String) -> Result<(), GameError> {
+    let caller_id = caller();
+    GAME_SERVICE.with(|service| {
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get_mut(&session_id)
+            .ok_or(GameError::GameNotFound)?;
+        game.kick_opponent(caller_id, ic_cdk::api::time())?;
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
     })
 }
 
 #[update]
-fn make_move(session_id: String, hand: u8, target_hand: u8) -> Result<(), String> {
+fn vote_rematch(session_id: String) -> Result<(), GameError> {
     let player_id = caller();
     GAME_SERVICE.with(|service| {
-        
-        let mut games = service.borrow_mut().get(&"chopsticks_game_service".to_string());
-        if let Some(mut game_service) = games {
-            if let Some(mut game) = game_service.games.get_mut(&session_id) {
-                game.make_move(player_id, hand, target_hand);
-                Ok(())
-            } else {
-                Err("Game not found".to_string())
-            }
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get_mut(&session_id)
+            .ok_or(GameError::GameNotFound)?;
+        game.vote_rematch(player_id, ic_cdk::api::time())?;
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })
+}
+
+#[update]
+fn make_move(session_id: String, mv: Move) -> Result<(), GameError> {
+    let player_id = caller();
+    GAME_SERVICE.with(|service| {
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get_mut(&session_id)
+            .ok_or(GameError::GameNotFound)?;
+        game.make_move(player_id, mv, ic_cdk::api::time())?;
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })
+}
+
+// Any caller may invoke this to flag an opponent who has let their clock run
+// out without making a move.
+#[update]
+fn claim_timeout(session_id: String) -> Result<(), GameError> {
+    GAME_SERVICE.with(|service| {
+        let mut borrow = service.borrow_mut();
+        let mut game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get_mut(&session_id)
+            .ok_or(GameError::GameNotFound)?;
+        game.claim_timeout(ic_cdk::api::time())?;
+        borrow.insert("chopsticks_game_service".to_string(), game_service);
+        Ok(())
+    })
+}
+
+#[query]
+fn get_revision(session_id: String) -> Result<u64, GameError> {
+    GAME_SERVICE.with(|service| {
+        let borrow = service.borrow();
+        let game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get(session_id.as_str())
+            .ok_or(GameError::GameNotFound)?;
+        Ok(game.revision)
+    })
+}
+
+// What `get_game_state` hands back to callers. Mirrors `Game` minus
+// `room_password`, which is a secret the board never needs to render and
+// must never round-trip to anyone who merely knows the session id.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct GameView {
+    session_id: String,
+    player1: Player,
+    player2: Option<Player>,
+    state: GameState,
+    current_turn: Turn,
+    clock: Option<GameClock>,
+    revision: u64,
+    last_activity: u64,
+    room_name: String,
+    is_private: bool,
+    rematch_votes: Vec<Principal>,
+    has_moved: bool,
+}
+
+impl From<Game> for GameView {
+    fn from(game: Game) -> Self {
+        GameView {
+            session_id: game.session_id,
+            player1: game.player1,
+            player2: game.player2,
+            state: game.state,
+            current_turn: game.current_turn,
+            clock: game.clock,
+            revision: game.revision,
+            last_activity: game.last_activity,
+            room_name: game.room_name,
+            is_private: game.is_private,
+            rematch_votes: game.rematch_votes,
+            has_moved: game.has_moved,
         }
-        else {
-            Ok(())
+    }
+}
+
+// `since_revision` lets a long-polling caller skip the payload entirely when
+// nothing has changed since the revision it already has.
+#[query]
+fn get_game_state(session_id: String, since_revision: Option<u64>) -> Result<Option<GameView>, GameError> {
+    GAME_SERVICE.with(|service| {
+        let borrow = service.borrow();
+        let game_service = borrow
+            .get(&"chopsticks_game_service".to_string())
+            .ok_or(GameError::ServiceUninitialized)?;
+        let game = game_service
+            .games
+            .get(session_id.as_str())
+            .ok_or(GameError::GameNotFound)?;
+        if since_revision == Some(game.revision) {
+            return Ok(None);
         }
+        let mut live = game.clone();
+        live.apply_live_clock(ic_cdk::api::time());
+        Ok(Some(GameView::from(live)))
     })
 }
 
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct GameCounts {
+    active: u64,
+    waiting: u64,
+    finished: u64,
+}
+
+// Lets operators observe how many games are live vs. sitting around waiting
+// to be reaped.
 #[query]
-fn get_game_state(session_id: String) -> Result<Game, String> {
+fn get_game_counts() -> GameCounts {
     GAME_SERVICE.with(|service| {
-        let games = service.borrow();
-        if let Some(game_service) = games.get(&"chopsticks_game_service".to_string()) {
-            if let Some(game) = game_service.games.get(session_id.as_str()){
-                Ok(game.clone())
-            }
-            else{
-                Err("Game not found".to_string())
+        let borrow = service.borrow();
+        let mut counts = GameCounts { active: 0, waiting: 0, finished: 0 };
+        if let Some(game_service) = borrow.get(&"chopsticks_game_service".to_string()) {
+            for game in game_service.games.values() {
+                match game.state {
+                    GameState::InProgress => counts.active += 1,
+                    GameState::WaitingForPlayer => counts.waiting += 1,
+                    GameState::Finished { .. } => counts.finished += 1,
+                }
             }
-        } else {
-            Err("Game not found".to_string())
         }
+        counts
     })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(byte: u8) -> Principal {
+        Principal::from_slice(&[byte])
+    }
+
+    fn clocked_game(initial_nanos: i64, per_move_increment_nanos: i64) -> Game {
+        let spec = ClockSpec { initial_nanos, per_move_increment_nanos };
+        let mut game = Game::new(principal(1), 0, Some(spec));
+        let player2 = Player { id: principal(2), game: Some(game.session_id.clone()), left_hand: 1, right_hand: 1 };
+        game.join(player2, 0).unwrap();
+        game
+    }
+
+    // `current_turn` is randomized at `Game::new`, so tests that drive a move
+    // look up whoever is actually on the clock rather than assuming player1.
+    fn active_player_id(game: &Game) -> Principal {
+        match game.current_turn {
+            Turn::Player1 => game.player1.id,
+            Turn::Player2 => game.player2.as_ref().unwrap().id,
+        }
+    }
+
+    #[test]
+    fn make_move_awards_timeout_win_when_active_clock_has_run_out() {
+        let mut game = clocked_game(10, 0);
+        let mover = active_player_id(&game);
+        let opponent = match game.current_turn {
+            Turn::Player1 => game.player2.as_ref().unwrap().id,
+            Turn::Player2 => game.player1.id,
+        };
+
+        // The active player's 10ns budget is long gone by the time they move.
+        let result = game.make_move(mover, Move::Tap { from_hand: 0, target_hand: 0 }, 1_000);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(game.state, GameState::Finished { winner: opponent });
+    }
+
+    #[test]
+    fn make_move_credits_the_increment_after_elapsed_time_is_deducted() {
+        let mut game = clocked_game(60_000_000_000, 2_000_000_000);
+        let pre_move_turn = game.current_turn.clone();
+        let mover_id = active_player_id(&game);
+
+        game.make_move(mover_id, Move::Tap { from_hand: 0, target_hand: 0 }, 5_000_000_000).unwrap();
+
+        // 60s budget, minus 5s elapsed, plus the 2s increment for the move just played.
+        let clock = game.clock.as_ref().unwrap();
+        let movers_clock = match pre_move_turn {
+            Turn::Player1 => clock.player1,
+            Turn::Player2 => clock.player2,
+        };
+        assert_eq!(movers_clock.remaining_nanos, 57_000_000_000);
+    }
+
+    #[test]
+    fn split_rejects_uneven_and_no_op_redistributions() {
+        let mut game = clocked_game(60_000_000_000, 0);
+        let mover_id = active_player_id(&game);
+
+        assert_eq!(
+            game.make_move(mover_id, Move::Split { left: 2, right: 1 }, 0),
+            Err(GameError::InvalidMove)
+        );
+        assert_eq!(
+            game.make_move(mover_id, Move::Split { left: 1, right: 1 }, 0),
+            Err(GameError::InvalidMove)
+        );
+        assert_eq!(
+            game.make_move(mover_id, Move::Split { left: 5, right: 0 }, 0),
+            Err(GameError::InvalidMove)
+        );
+    }
+
+    #[test]
+    fn kick_opponent_is_rejected_once_a_move_has_been_played() {
+        let mut game = clocked_game(60_000_000_000, 0);
+        let master_id = game.player1.id;
+        let mover_id = active_player_id(&game);
+
+        game.make_move(mover_id, Move::Tap { from_hand: 0, target_hand: 0 }, 0).unwrap();
+
+        assert_eq!(game.kick_opponent(master_id, 0), Err(GameError::GameNotInProgress));
+    }
+
+    #[test]
+    fn kick_opponent_succeeds_before_any_move_is_played() {
+        let mut game = clocked_game(60_000_000_000, 0);
+        let master_id = game.player1.id;
+
+        assert_eq!(game.kick_opponent(master_id, 0), Ok(()));
+        assert!(game.player2.is_none());
+        assert_eq!(game.state, GameState::WaitingForPlayer);
+    }
+
+    #[test]
+    fn two_votes_reset_hands_and_clocks_for_a_rematch() {
+        let mut game = clocked_game(60_000_000_000, 0);
+        let player1_id = game.player1.id;
+        let player2_id = game.player2.as_ref().unwrap().id;
+
+        // Drive the game to Finished with a depleted clock on one side.
+        game.clock.as_mut().unwrap().player1.remaining_nanos = -5;
+        game.state = GameState::Finished { winner: player2_id };
+
+        assert_eq!(game.vote_rematch(player1_id, 0), Ok(()));
+        assert_eq!(game.state, GameState::Finished { winner: player2_id });
+
+        assert_eq!(game.vote_rematch(player2_id, 0), Ok(()));
+        assert_eq!(game.state, GameState::InProgress);
+        assert!(game.rematch_votes.is_empty());
+        assert_eq!(game.player1.left_hand, 1);
+        assert_eq!(game.player1.right_hand, 1);
+        assert_eq!(game.player2.as_ref().unwrap().left_hand, 1);
+        assert_eq!(game.player2.as_ref().unwrap().right_hand, 1);
+        assert_eq!(game.clock.as_ref().unwrap().player1.remaining_nanos, 60_000_000_000);
+    }
+}
+
 // Export the candid interface
 ic_cdk_macros::export_candid!();